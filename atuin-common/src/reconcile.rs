@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::record::Record;
+
+/// The actionable result of reconciling one side of a [`crate::record::RecordIndex::diff`]
+/// entry: the records the other side is missing, the ids we need to fetch
+/// ourselves, and any divergent forks spotted along the way.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reconciliation {
+    /// Records the other side is missing, oldest first.
+    pub to_upload: Vec<Record>,
+
+    /// Ids of records we are missing, oldest first. Resolve these against the
+    /// server's record-batch endpoint (or the equivalent local lookup) to get
+    /// the actual `Record`s.
+    pub to_download: Vec<Uuid>,
+
+    /// Pairs of record ids that are both children of the same parent — two
+    /// concurrent writers appended to the same tail without seeing each
+    /// other's write. These should be surfaced to the caller rather than
+    /// silently preferring one over the other.
+    pub forks: Vec<(Uuid, Uuid)>,
+}
+
+/// Reconcile a single `(host, tag)` diff entry by walking both tails backward
+/// along `parent` links until they reach a shared ancestor (or run out of
+/// chain), producing the minimal ordered set of records each side is missing.
+///
+/// `local` and `remote` look up a single record by id on each side. `local`
+/// is expected to be a cheap in-memory or on-disk lookup; `remote` may hit the
+/// network, so it is only ever called while walking the remote tail.
+///
+/// Side effect: every remote record this walks - including ones that turn
+/// out forked or otherwise held back from `to_download` - is folded into the
+/// process-wide [`crate::record::Hlc`] clock via
+/// [`crate::record::Record::observe_remote_clock`]. This is not a pure
+/// diffing computation; calling it advances global clock state.
+pub fn reconcile(
+    local_tail: Option<Uuid>,
+    remote_tail: Option<Uuid>,
+    local: impl Fn(Uuid) -> Option<Record>,
+    remote: impl Fn(Uuid) -> Option<Record>,
+) -> Reconciliation {
+    // Walk the local chain fully into memory. We also index it by parent, so
+    // that while walking the remote chain we can spot a remote record whose
+    // parent already has a *different* local child - a fork.
+    let mut local_chain: HashMap<Uuid, Record> = HashMap::new();
+    let mut local_children: HashMap<Uuid, Uuid> = HashMap::new();
+
+    let mut cursor = local_tail;
+    while let Some(id) = cursor {
+        let Some(record) = local(id) else { break };
+
+        if let Some(parent) = record.parent {
+            local_children.insert(parent, id);
+        }
+
+        cursor = record.parent;
+        local_chain.insert(id, record);
+    }
+
+    let mut forks = Vec::new();
+    // Ids on either side that turned out to be one half of a fork. These are
+    // held back from the transfer sets below - surfaced via `forks` instead
+    // of silently clobbered by whichever side happened to sync first.
+    let mut forked_local_ids: HashSet<Uuid> = HashSet::new();
+    let mut forked_remote_ids: HashSet<Uuid> = HashSet::new();
+    let mut common_ancestor = None;
+
+    // Walk the remote chain fully before deciding what to download: a fork
+    // can only be recognised once we reach the diverging parent, but by then
+    // we've already walked past (and would otherwise have queued) everything
+    // built on top of it. Collect first, filter after.
+    let mut remote_chain: Vec<(Uuid, Record)> = Vec::new();
+    let mut cursor = remote_tail;
+    while let Some(id) = cursor {
+        if local_chain.contains_key(&id) {
+            common_ancestor = Some(id);
+            break;
+        }
+
+        let Some(record) = remote(id) else { break };
+
+        // We've now seen this remote record's HLC, whether or not it ends up
+        // forked or held back below - fold it into the local clock so records
+        // we author from here on never regress relative to it.
+        record.observe_remote_clock();
+
+        cursor = record.parent;
+        remote_chain.push((id, record));
+    }
+
+    for (id, record) in &remote_chain {
+        if let Some(parent) = record.parent {
+            if let Some(&local_child) = local_children.get(&parent) {
+                if local_child != *id {
+                    forks.push((local_child, *id));
+                    forked_local_ids.insert(local_child);
+                    forked_remote_ids.insert(*id);
+                }
+            }
+        }
+    }
+
+    // A forked record, and anything chained on top of it, can't be reconciled
+    // onto the other side without reattaching to a parent it doesn't have -
+    // hold back the fork and everything built on top of it, keeping only the
+    // shared history below the fork point.
+    let remote_cutoff = remote_chain
+        .iter()
+        .rposition(|(id, _)| forked_remote_ids.contains(id));
+    let to_download: Vec<Uuid> = remote_chain[remote_cutoff.map_or(0, |i| i + 1)..]
+        .iter()
+        .map(|(id, _)| *id)
+        .rev()
+        .collect();
+
+    let mut local_walk: Vec<(Uuid, Record)> = Vec::new();
+    let mut cursor = local_tail;
+    while let Some(id) = cursor {
+        if Some(id) == common_ancestor {
+            break;
+        }
+
+        let Some(record) = local_chain.get(&id) else {
+            break;
+        };
+
+        cursor = record.parent;
+        local_walk.push((id, record.clone()));
+    }
+
+    let local_cutoff = local_walk
+        .iter()
+        .rposition(|(id, _)| forked_local_ids.contains(id));
+    let to_upload: Vec<Record> = local_walk[local_cutoff.map_or(0, |i| i + 1)..]
+        .iter()
+        .map(|(_, record)| record.clone())
+        .rev()
+        .collect();
+
+    debug_assert!(to_download.iter().all(|id| !forked_remote_ids.contains(id)));
+
+    Reconciliation {
+        to_upload,
+        to_download,
+        forks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn root() -> Record {
+        Record::builder()
+            .host(crate::utils::uuid_v7())
+            .version("v1".into())
+            .tag("history".into())
+            .data(vec![0])
+            .build()
+    }
+
+    fn store(records: &[Record]) -> impl Fn(Uuid) -> Option<Record> + '_ {
+        move |id| records.iter().find(|r| r.id == id).cloned()
+    }
+
+    #[test]
+    fn remote_ahead_downloads_the_missing_suffix() {
+        let r1 = root();
+        let r2 = r1.new_child(vec![1]);
+        let r3 = r2.new_child(vec![2]);
+
+        let local = vec![r1.clone()];
+        let remote = vec![r1.clone(), r2.clone(), r3.clone()];
+
+        let result = reconcile(Some(r1.id), Some(r3.id), store(&local), store(&remote));
+
+        assert_eq!(result.to_download, vec![r2.id, r3.id]);
+        assert!(result.to_upload.is_empty());
+        assert!(result.forks.is_empty());
+    }
+
+    #[test]
+    fn local_ahead_uploads_the_missing_suffix() {
+        let r1 = root();
+        let r2 = r1.new_child(vec![1]);
+        let r3 = r2.new_child(vec![2]);
+
+        let local = vec![r1.clone(), r2.clone(), r3.clone()];
+        let remote = vec![r1.clone()];
+
+        let result = reconcile(Some(r3.id), Some(r1.id), store(&local), store(&remote));
+
+        assert_eq!(
+            result.to_upload.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![r2.id, r3.id]
+        );
+        assert!(result.to_download.is_empty());
+        assert!(result.forks.is_empty());
+    }
+
+    #[test]
+    fn divergent_children_are_reported_as_a_fork() {
+        let r1 = root();
+        let local_child = r1.new_child(vec![1]);
+        let remote_child = r1.new_child(vec![2]);
+
+        let local = vec![r1.clone(), local_child.clone()];
+        let remote = vec![r1.clone(), remote_child.clone()];
+
+        let result = reconcile(
+            Some(local_child.id),
+            Some(remote_child.id),
+            store(&local),
+            store(&remote),
+        );
+
+        assert_eq!(result.forks, vec![(local_child.id, remote_child.id)]);
+
+        // Forked records are surfaced, not transferred - the caller has to
+        // resolve the conflict rather than have one side silently clobber
+        // the other.
+        assert!(result.to_download.is_empty());
+        assert!(result.to_upload.is_empty());
+    }
+
+    #[test]
+    fn descendants_of_a_fork_are_also_held_back() {
+        let r1 = root();
+        let local_child = r1.new_child(vec![1]);
+        let local_grandchild = local_child.new_child(vec![2]);
+        let remote_child = r1.new_child(vec![3]);
+        let remote_grandchild = remote_child.new_child(vec![4]);
+
+        let local = vec![r1.clone(), local_child.clone(), local_grandchild.clone()];
+        let remote = vec![r1.clone(), remote_child.clone(), remote_grandchild.clone()];
+
+        let result = reconcile(
+            Some(local_grandchild.id),
+            Some(remote_grandchild.id),
+            store(&local),
+            store(&remote),
+        );
+
+        assert_eq!(result.forks, vec![(local_child.id, remote_child.id)]);
+
+        // Neither grandchild can be reconciled onto the other side without
+        // reattaching to a parent it doesn't have, so both stay back along
+        // with the fork itself.
+        assert!(result.to_download.is_empty());
+        assert!(result.to_upload.is_empty());
+    }
+
+    #[test]
+    fn identical_tails_reconcile_to_nothing() {
+        let r1 = root();
+        let local = vec![r1.clone()];
+
+        let result = reconcile(Some(r1.id), Some(r1.id), store(&local), store(&local));
+
+        assert_eq!(result, Reconciliation::default());
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::Mutex;
 
 use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
@@ -6,6 +7,115 @@ use uuid::Uuid;
 
 pub type Diff = Vec<(Uuid, String, Uuid)>;
 
+/// Number of bits of the packed timestamp given over to the logical counter.
+/// The remaining (high) bits hold the physical time, in milliseconds since
+/// the unix epoch.
+const HLC_LOGICAL_BITS: u32 = 16;
+const HLC_PHYSICAL_MASK: u64 = (1 << (64 - HLC_LOGICAL_BITS)) - 1;
+
+/// A Hybrid Logical Clock timestamp.
+///
+/// Clocks are tricksy: wall clocks drift, and can even go backwards. An HLC
+/// pairs a physical time (milliseconds since the epoch, clamped to never
+/// regress) with a logical counter that breaks ties, so that timestamps
+/// assigned across different hosts can still be totally ordered and respect
+/// happens-before relationships, even when a host's clock is wrong.
+///
+/// Packed into a `u64` as 48 bits of physical time followed by 16 bits of
+/// logical counter, for storage in [`Record::hlc`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hlc {
+    pub physical: u64,
+    pub logical: u16,
+}
+
+impl Hlc {
+    pub fn pack(self) -> u64 {
+        ((self.physical & HLC_PHYSICAL_MASK) << HLC_LOGICAL_BITS) | self.logical as u64
+    }
+
+    pub fn unpack(timestamp: u64) -> Hlc {
+        Hlc {
+            physical: (timestamp >> HLC_LOGICAL_BITS) & HLC_PHYSICAL_MASK,
+            logical: timestamp as u16,
+        }
+    }
+
+    /// Bump `logical` by one, carrying into `physical` instead of overflowing
+    /// if 65536 events have already landed in the same physical millisecond.
+    /// Losing a tick of physical time this way is harmless - it still only
+    /// ever moves forward - whereas wrapping `logical` back to 0 would make a
+    /// later event sort before an earlier one.
+    fn tick(physical: u64, logical: u16) -> (u64, u16) {
+        match logical.checked_add(1) {
+            Some(logical) => (physical, logical),
+            None => (physical + 1, 0),
+        }
+    }
+
+    /// Advance the clock for a locally generated event.
+    fn next_local(self, wall_now_ms: u64) -> Hlc {
+        let physical = self.physical.max(wall_now_ms);
+
+        let (physical, logical) = if physical == self.physical {
+            Self::tick(physical, self.logical)
+        } else {
+            (physical, 0)
+        };
+
+        Hlc { physical, logical }
+    }
+
+    /// Merge in a remote HLC observed alongside a local event.
+    fn next_remote(self, remote: Hlc, wall_now_ms: u64) -> Hlc {
+        let physical = self.physical.max(remote.physical).max(wall_now_ms);
+
+        let (physical, logical) = if physical == self.physical && physical == remote.physical {
+            Self::tick(physical, self.logical.max(remote.logical))
+        } else if physical == remote.physical {
+            Self::tick(physical, remote.logical)
+        } else if physical == self.physical {
+            Self::tick(physical, self.logical)
+        } else {
+            (physical, 0)
+        };
+
+        Hlc { physical, logical }
+    }
+
+    /// The process-wide clock, shared by every record created on this host so that
+    /// timestamps it hands out are monotonic even across concurrent callers.
+    fn global() -> &'static Mutex<Hlc> {
+        static CLOCK: Mutex<Hlc> = Mutex::new(Hlc {
+            physical: 0,
+            logical: 0,
+        });
+
+        &CLOCK
+    }
+
+    fn wall_now_ms() -> u64 {
+        (chrono::Utc::now().timestamp_nanos() / 1_000_000) as u64
+    }
+
+    /// Tick the local clock forward for a newly created record.
+    pub fn now() -> Hlc {
+        let mut clock = Self::global().lock().expect("hlc clock lock poisoned");
+        *clock = clock.next_local(Self::wall_now_ms());
+        *clock
+    }
+
+    /// Fold a timestamp seen on a remote record into the local clock, so that
+    /// records we author from this point on always sort after it. The sync path
+    /// should call this for every remote record it ingests, so the local clock
+    /// never regresses relative to records we've already seen.
+    pub fn observe_remote(remote: Hlc) -> Hlc {
+        let mut clock = Self::global().lock().expect("hlc clock lock poisoned");
+        *clock = clock.next_remote(remote, Self::wall_now_ms());
+        *clock
+    }
+}
+
 /// A single record stored inside of our local database
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TypedBuilder)]
 pub struct Record {
@@ -30,6 +140,13 @@ pub struct Record {
     #[builder(default = chrono::Utc::now().timestamp_nanos() as u64)]
     pub timestamp: u64,
 
+    /// A Hybrid Logical Clock timestamp (see [`Hlc`]), packed into a u64. Unlike
+    /// `timestamp`, this is safe to rely on for ordering: records authored on
+    /// different hosts merge into a total, causally-consistent order without
+    /// depending on synchronized wall clocks.
+    #[builder(default = Hlc::now().pack())]
+    pub hlc: u64,
+
     /// The version the data in the entry conforms to
     // However we want to track versions for this tag, eg v2
     pub version: String,
@@ -39,6 +156,16 @@ pub struct Record {
 
     /// Some data. This can be anything you wish to store. Use the tag field to know how to handle it.
     pub data: Vec<u8>,
+
+    /// The global sequence number the server assigned this record, monotonic
+    /// per (user, host, tag). `None` until the server accepts it - clients
+    /// never set this themselves. Currently assigned by the server's
+    /// in-process `RecordLog`, not persisted as part of the durable record
+    /// store (see its docs). Lets a client that only knows it's missing a
+    /// suffix fetch exactly that suffix in ordered, bulk ranges instead of
+    /// walking `parent` pointers one record at a time.
+    #[builder(default)]
+    pub seq: Option<u64>,
 }
 
 impl Record {
@@ -51,6 +178,13 @@ impl Record {
             .data(data)
             .build()
     }
+
+    /// Feed this record's HLC into the local clock, as if it had just been
+    /// observed from a remote host. The sync path should call this whenever it
+    /// ingests a remote record, so the local clock never regresses.
+    pub fn observe_remote_clock(&self) -> Hlc {
+        Hlc::observe_remote(Hlc::unpack(self.hlc))
+    }
 }
 
 /// An index representing the current state of the record stores
@@ -146,7 +280,7 @@ impl RecordIndex {
 
 #[cfg(test)]
 mod tests {
-    use super::{Record, RecordIndex};
+    use super::{Hlc, Record, RecordIndex};
     use pretty_assertions::{assert_eq, assert_ne};
     use uuid::Uuid;
 
@@ -277,4 +411,79 @@ mod tests {
         assert_eq!(index1.diff(&index1).len(), 0);
         assert_eq!(index2.diff(&index2).len(), 0);
     }
+
+    #[test]
+    fn hlc_pack_unpack_roundtrip() {
+        let clock = Hlc {
+            physical: 1_700_000_000_000,
+            logical: 42,
+        };
+
+        assert_eq!(clock, Hlc::unpack(clock.pack()));
+    }
+
+    #[test]
+    fn hlc_local_events_are_monotonic() {
+        let a = Hlc {
+            physical: 100,
+            logical: 0,
+        };
+        let b = a.next_local(100);
+        let c = b.next_local(50);
+
+        // wall clock did not advance, so the logical counter ticks instead
+        assert_eq!(b, Hlc { physical: 100, logical: 1 });
+        assert_eq!(c, Hlc { physical: 100, logical: 2 });
+        assert!(c > b && b > a);
+    }
+
+    #[test]
+    fn hlc_remote_merge_never_regresses() {
+        let local = Hlc {
+            physical: 100,
+            logical: 5,
+        };
+        let remote = Hlc {
+            physical: 100,
+            logical: 9,
+        };
+
+        // physical ties on both sides, logical takes the max seen and ticks
+        let merged = local.next_remote(remote, 0);
+        assert_eq!(merged, Hlc { physical: 100, logical: 10 });
+        assert!(merged > local && merged > remote);
+
+        // a remote record from the past should never pull us backwards
+        let stale_remote = Hlc {
+            physical: 1,
+            logical: 0,
+        };
+        let merged = merged.next_remote(stale_remote, 0);
+        assert_eq!(merged, Hlc { physical: 100, logical: 11 });
+    }
+
+    #[test]
+    fn record_new_child_advances_clock() {
+        let record = test_record();
+        let child = record.new_child(vec![1, 2, 3]);
+
+        assert!(Hlc::unpack(child.hlc) > Hlc::unpack(record.hlc));
+    }
+
+    #[test]
+    fn hlc_tick_carries_into_physical_on_overflow() {
+        let maxed = Hlc {
+            physical: 100,
+            logical: u16::MAX,
+        };
+
+        // Another event landing in the same physical millisecond would
+        // normally just bump `logical`, but it's already at the top of its
+        // range - carry into `physical` instead of wrapping back to 0, which
+        // would make this event sort *before* the ones before it.
+        let next = maxed.next_local(100);
+
+        assert_eq!(next, Hlc { physical: 101, logical: 0 });
+        assert!(next > maxed);
+    }
 }
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use uuid::Uuid;
+
+use crate::record::Record;
+
+/// The three states the sync engine can be in.
+///
+/// `Idle` and `Syncing` both allow any number of concurrent readers (search
+/// queries, the server's `index`/`post` handlers) through [`StateLock::read`].
+/// `Snapshotting` is exclusive: a snapshot compacts a host/tag's record chain
+/// into a point-in-time checkpoint, and must never run alongside something
+/// else reading or mutating that chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    Idle,
+    Syncing,
+    Snapshotting,
+}
+
+/// Coordinates access to a piece of shared state (typically a record store)
+/// between ordinary concurrent reads and an exclusive snapshot/compaction
+/// phase.
+///
+/// This is a thin wrapper around a [`tokio::sync::RwLock`]: `read()` maps
+/// onto the shared lock, so search queries and a running sync never block
+/// each other, while `snapshot()` takes the exclusive lock for exactly as
+/// long as the compaction closure runs. `state()` reports which of the three
+/// phases is currently in effect, mostly for logging/metrics.
+pub struct StateLock<T> {
+    inner: RwLock<T>,
+    state: Mutex<SyncState>,
+}
+
+impl<T> StateLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: RwLock::new(value),
+            state: Mutex::new(SyncState::Idle),
+        }
+    }
+
+    pub fn state(&self) -> SyncState {
+        *self.state.lock().expect("state lock poisoned")
+    }
+
+    /// Acquire a shared read guard. Any number of these can be held
+    /// concurrently, including while a sync pass holds its own `read()` guard
+    /// to ingest records. Blocks only while a `snapshot()` is in progress.
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.read().await
+    }
+
+    /// Mark the start of a sync pass for as long as the returned guard is
+    /// held. This does not take the lock itself — sync reads and writes the
+    /// record store through its own `read()`/`snapshot()` calls like anyone
+    /// else — it only updates `state()` for observability, and so that a
+    /// `Snapshotting` pass starting concurrently is a visible, diagnosable
+    /// overlap rather than a silent one.
+    pub fn begin_sync(&self) -> SyncGuard<'_, T> {
+        *self.state.lock().expect("state lock poisoned") = SyncState::Syncing;
+        SyncGuard { lock: self }
+    }
+
+    /// Run `task` with exclusive access to the underlying value, holding the
+    /// write lock for exactly as long as it takes to run. `state()` reports
+    /// `Snapshotting` from the moment this is called, including while it
+    /// waits for the write lock. Concurrent `read()` callers wait until it
+    /// completes.
+    ///
+    /// Restores whatever `state()` reported before the snapshot started -
+    /// not unconditionally `Idle` - so a sync pass that was already in
+    /// progress (via `begin_sync`) when the snapshot ran is still reported
+    /// as `Syncing` afterwards. The restore happens via a drop guard, so it
+    /// runs even if `task` panics, instead of leaving `state()` stuck at
+    /// `Snapshotting` forever.
+    pub async fn snapshot<R>(&self, task: impl FnOnce(&mut T) -> R) -> R {
+        let previous = {
+            let mut state = self.state.lock().expect("state lock poisoned");
+            let previous = *state;
+            *state = SyncState::Snapshotting;
+            previous
+        };
+        let _restore = RestoreState {
+            lock: self,
+            previous,
+        };
+
+        let mut guard = self.inner.write().await;
+        task(&mut guard)
+    }
+}
+
+/// Restores [`SyncState::Idle`] when a sync pass finishes, including if it
+/// bails out early via `?` or a panic unwinds through it.
+pub struct SyncGuard<'a, T> {
+    lock: &'a StateLock<T>,
+}
+
+impl<'a, T> Drop for SyncGuard<'a, T> {
+    fn drop(&mut self) {
+        *self.lock.state.lock().expect("state lock poisoned") = SyncState::Idle;
+    }
+}
+
+/// Restores whichever [`SyncState`] was in effect before a `snapshot()` call
+/// started, on drop - including on panic, since `Drop::drop` still runs
+/// while unwinding.
+struct RestoreState<'a, T> {
+    lock: &'a StateLock<T>,
+    previous: SyncState,
+}
+
+impl<'a, T> Drop for RestoreState<'a, T> {
+    fn drop(&mut self) {
+        *self.lock.state.lock().expect("state lock poisoned") = self.previous;
+    }
+}
+
+/// All records for a single host/tag chain, keyed by id, as held in memory
+/// while a snapshot compacts them.
+pub type RecordChain = HashMap<Uuid, Record>;
+
+/// Compact a host/tag's record chain (the linked list built by
+/// [`Record::new_child`]) down to a single point-in-time checkpoint: `tail`,
+/// reparented onto `None`, plus the ids of every record before `tail` in the
+/// chain, the root included, which can now be pruned.
+///
+/// This is the operation a `Snapshotting` pass performs while holding
+/// [`StateLock::snapshot`]'s exclusive access, so the chain it walks can't be
+/// extended or torn out from under it mid-walk.
+///
+/// Nothing in this checkout calls `StateLock::snapshot` with this yet - the
+/// trigger (a scheduled maintenance pass) and the durable chain storage it
+/// would read from and prune (an extension to `Database`) both live outside
+/// this diff.
+pub fn compact_chain(records: &RecordChain, tail: Uuid) -> Option<(Record, Vec<Uuid>)> {
+    let mut checkpoint = records.get(&tail)?.clone();
+    let mut to_prune = Vec::new();
+
+    let mut parent = checkpoint.parent;
+    while let Some(id) = parent {
+        let Some(record) = records.get(&id) else {
+            break;
+        };
+
+        to_prune.push(id);
+        parent = record.parent;
+    }
+
+    checkpoint.parent = None;
+    Some((checkpoint, to_prune))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain_of(host: Uuid, tag: &str, len: usize) -> (RecordChain, Uuid) {
+        let mut records = HashMap::new();
+
+        let mut current = Record::builder()
+            .host(host)
+            .version("v1".into())
+            .tag(tag.into())
+            .data(vec![0])
+            .build();
+        records.insert(current.id, current.clone());
+
+        for _ in 1..len {
+            current = current.new_child(vec![0]);
+            records.insert(current.id, current.clone());
+        }
+
+        (records, current.id)
+    }
+
+    #[test]
+    fn compact_chain_reparents_tail_onto_none() {
+        let host = crate::utils::uuid_v7();
+        let (records, tail) = chain_of(host, "history", 5);
+
+        let (checkpoint, pruned) = compact_chain(&records, tail).expect("chain should compact");
+
+        assert_eq!(checkpoint.id, tail);
+        assert_eq!(checkpoint.parent, None);
+        assert_eq!(pruned.len(), 4);
+    }
+
+    #[test]
+    fn compact_chain_missing_tail_returns_none() {
+        let records = RecordChain::new();
+        assert_eq!(compact_chain(&records, crate::utils::uuid_v7()), None);
+    }
+
+    #[tokio::test]
+    async fn snapshot_takes_exclusive_access() {
+        let lock = StateLock::new(0_u32);
+
+        assert_eq!(lock.state(), SyncState::Idle);
+
+        let result = lock
+            .snapshot(|value| {
+                *value += 1;
+                *value
+            })
+            .await;
+
+        assert_eq!(result, 1);
+        assert_eq!(lock.state(), SyncState::Idle);
+    }
+
+    #[tokio::test]
+    async fn begin_sync_restores_idle_on_drop() {
+        let lock = StateLock::new(());
+
+        {
+            let _guard = lock.begin_sync();
+            assert_eq!(lock.state(), SyncState::Syncing);
+        }
+
+        assert_eq!(lock.state(), SyncState::Idle);
+    }
+
+    #[tokio::test]
+    async fn snapshot_restores_syncing_not_idle() {
+        let lock = StateLock::new(0_u32);
+        let _sync_guard = lock.begin_sync();
+        assert_eq!(lock.state(), SyncState::Syncing);
+
+        lock.snapshot(|value| *value += 1).await;
+
+        // A sync pass was already in progress when the snapshot ran - it
+        // should come back to `Syncing`, not be clobbered to `Idle`.
+        assert_eq!(lock.state(), SyncState::Syncing);
+    }
+
+    #[tokio::test]
+    async fn snapshot_restores_state_even_if_task_panics() {
+        let lock = std::sync::Arc::new(StateLock::new(0_u32));
+        let lock2 = lock.clone();
+
+        let result = tokio::spawn(async move {
+            lock2.snapshot(|_value| panic!("boom")).await;
+        })
+        .await;
+
+        assert!(result.is_err(), "the spawned task should have panicked");
+        assert_eq!(lock.state(), SyncState::Idle);
+    }
+}
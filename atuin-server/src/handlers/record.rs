@@ -1,5 +1,9 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Query, State},
+    Json,
+};
 use http::StatusCode;
+use serde::Deserialize;
 use tracing::{error, instrument};
 
 use super::{ErrorResponse, ErrorResponseStatus, RespExt};
@@ -8,13 +12,50 @@ use atuin_server_database::Database;
 
 use atuin_common::record::{Record, RecordIndex};
 
+/// The default, and max, number of records returned by a single call to `next`.
+const RECORD_PAGE_DEFAULT: u64 = 1000;
+const RECORD_PAGE_MAX: u64 = 1000;
+
+/// The max number of ids a single call to `batch` will resolve, mirroring
+/// `RECORD_PAGE_MAX` so a caller can't force an unbounded fetch in one go.
+const RECORD_BATCH_MAX: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct NextRecordParams {
+    host: uuid::Uuid,
+    tag: String,
+
+    /// The global sequence number to start after. Omit to fetch from the start
+    /// of this (host, tag)'s log.
+    after: Option<u64>,
+
+    limit: Option<u64>,
+}
+
+/// Accepts new records, and assigns each one a sequence number that is
+/// monotonic per (user, host, tag). This lets a client that only has a stale
+/// `RecordIndex::diff` come back later and ask for the exact suffix it is
+/// missing via [`next`], instead of chasing `parent` pointers one at a time.
+///
+/// Assignment currently goes through [`crate::record_log::RecordLog`], an
+/// in-process stand-in - see its docs for why that's not yet the durable,
+/// restart-safe implementation this feature needs.
 #[instrument(skip_all, fields(user.id = user.id))]
 pub async fn post<DB: Database>(
     UserAuth(user): UserAuth,
     state: State<AppState<DB>>,
-    Json(records): Json<Vec<Record>>,
+    Json(mut records): Json<Vec<Record>>,
 ) -> Result<(), ErrorResponseStatus<'static>> {
-    let State(AppState { database, settings }) = state;
+    let State(AppState {
+        database,
+        settings,
+        record_lock,
+        record_log,
+    }) = state;
+
+    // Any number of posts/indexes can run concurrently with each other and
+    // with a sync pass; only an in-progress snapshot excludes them.
+    let _guard = record_lock.read().await;
 
     tracing::debug!(
         count = records.len(),
@@ -33,6 +74,14 @@ pub async fn post<DB: Database>(
         );
     }
 
+    // Assign each record the next sequence number for its (host, tag) under
+    // this user, atomically, before it lands anywhere durable. This is what
+    // lets a client resume a sync by sequence number via `next`, instead of
+    // re-walking `parent` pointers one record at a time.
+    for record in records.iter_mut() {
+        record_log.append(user.id, record);
+    }
+
     if let Err(e) = database.add_records(&user, &records).await {
         error!("failed to add record: {}", e);
 
@@ -48,7 +97,14 @@ pub async fn index<DB: Database>(
     UserAuth(user): UserAuth,
     state: State<AppState<DB>>,
 ) -> Result<Json<RecordIndex>, ErrorResponseStatus<'static>> {
-    let State(AppState { database, settings }) = state;
+    let State(AppState {
+        database,
+        settings: _,
+        record_lock,
+        record_log: _,
+    }) = state;
+
+    let _guard = record_lock.read().await;
 
     let index = match database.tail_records(&user).await {
         Ok(index) => index,
@@ -68,3 +124,64 @@ pub async fn index<DB: Database>(
 
     Ok(Json(record_index))
 }
+
+/// Return an ordered range of records for a single (host, tag) pair, starting
+/// just after `after` (or from the start of the log, if omitted).
+///
+/// This is the bulk counterpart to [`index`]/[`post`]'s tail comparison: once a
+/// client has diffed its `RecordIndex` against ours and knows it is missing a
+/// suffix, it can page through that suffix here by global sequence number
+/// instead of walking `parent` pointers one record at a time.
+#[instrument(skip_all, fields(user.id = user.id))]
+pub async fn next<DB: Database>(
+    UserAuth(user): UserAuth,
+    state: State<AppState<DB>>,
+    Query(params): Query<NextRecordParams>,
+) -> Result<Json<Vec<Record>>, ErrorResponseStatus<'static>> {
+    let State(AppState {
+        record_lock,
+        record_log,
+        ..
+    }) = state;
+
+    let _guard = record_lock.read().await;
+
+    let limit = params
+        .limit
+        .unwrap_or(RECORD_PAGE_DEFAULT)
+        .min(RECORD_PAGE_MAX);
+
+    let records = record_log.next(user.id, params.host, &params.tag, params.after, limit);
+
+    Ok(Json(records))
+}
+
+/// Fetch a specific, caller-chosen set of records by id.
+///
+/// Pairs with `atuin_common::reconcile::reconcile`: once a client has walked
+/// its own and our tails to a common ancestor and worked out exactly which
+/// ids it's missing, it posts that list here instead of re-deriving them
+/// one `parent` pointer at a time.
+#[instrument(skip_all, fields(user.id = user.id))]
+pub async fn batch<DB: Database>(
+    UserAuth(_user): UserAuth,
+    state: State<AppState<DB>>,
+    Json(ids): Json<Vec<uuid::Uuid>>,
+) -> Result<Json<Vec<Record>>, ErrorResponseStatus<'static>> {
+    if ids.len() > RECORD_BATCH_MAX {
+        return Err(
+            ErrorResponse::reply("too many ids requested in a single batch")
+                .with_status(StatusCode::BAD_REQUEST),
+        );
+    }
+
+    let State(AppState {
+        record_lock,
+        record_log,
+        ..
+    }) = state;
+
+    let _guard = record_lock.read().await;
+
+    Ok(Json(record_log.get(&ids)))
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use atuin_common::record::Record;
+use uuid::Uuid;
+
+/// An ordered, append-only log of records, keyed by `(user, host, tag)`, that
+/// assigns each incoming record a sequence number monotonic within its key.
+///
+/// This is an in-process stand-in for the real feature this request asks for:
+/// a monotonic, atomically-assigned per-`(user, host, tag)` sequence number.
+/// A durable version needs the assignment to happen as part of the same
+/// transaction that persists the record - i.e. an addition to the
+/// `atuin_server_database::Database` trait and its backing store, which
+/// aren't part of this checkout. Until that lands, `RecordLog` is
+/// process-local only: a restart resets every counter to 0 and forgets every
+/// record it had served, which would collide with anything a real durable
+/// store had already assigned and silently empty out `next`/`batch` for
+/// anything accepted before the restart. Do not treat this as the durable
+/// implementation the request describes - it is the part that still needs to
+/// move into `Database`.
+#[derive(Default)]
+pub struct RecordLog {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // The next sequence number to hand out, per (user, host, tag).
+    next_seq: HashMap<(Uuid, Uuid, String), u64>,
+    by_id: HashMap<Uuid, Record>,
+    // Append order per (user, host, tag), so `next` can page by seq cheaply
+    // without scanning every record the user owns.
+    order: HashMap<(Uuid, Uuid, String), Vec<Uuid>>,
+}
+
+impl RecordLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assign the next sequence number for `record`'s (host, tag) under
+    /// `user_id`, atomically, and store it. Sets `record.seq` in place.
+    pub fn append(&self, user_id: Uuid, record: &mut Record) {
+        let mut inner = self.inner.lock().expect("record log lock poisoned");
+        let key = (user_id, record.host, record.tag.clone());
+
+        let seq = inner.next_seq.entry(key.clone()).or_insert(0);
+        let assigned = *seq;
+        *seq += 1;
+
+        record.seq = Some(assigned);
+
+        inner.order.entry(key).or_default().push(record.id);
+        inner.by_id.insert(record.id, record.clone());
+    }
+
+    /// Records for `(user_id, host, tag)` with a sequence number greater than
+    /// `after` (or all of them, if `after` is `None`), oldest first, capped
+    /// at `limit`.
+    pub fn next(
+        &self,
+        user_id: Uuid,
+        host: Uuid,
+        tag: &str,
+        after: Option<u64>,
+        limit: u64,
+    ) -> Vec<Record> {
+        let inner = self.inner.lock().expect("record log lock poisoned");
+        let key = (user_id, host, tag.to_string());
+
+        let Some(ids) = inner.order.get(&key) else {
+            return Vec::new();
+        };
+
+        ids.iter()
+            .filter_map(|id| inner.by_id.get(id))
+            .filter(|record| after.map_or(true, |after| record.seq.is_some_and(|seq| seq > after)))
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+
+    /// Look up a caller-chosen set of records by id, in whatever order they
+    /// were found in.
+    pub fn get(&self, ids: &[Uuid]) -> Vec<Record> {
+        let inner = self.inner.lock().expect("record log lock poisoned");
+        ids.iter()
+            .filter_map(|id| inner.by_id.get(id))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn record(host: Uuid, tag: &str) -> Record {
+        Record::builder()
+            .host(host)
+            .version("v1".into())
+            .tag(tag.into())
+            .data(vec![0])
+            .build()
+    }
+
+    #[test]
+    fn append_assigns_monotonic_seq_per_user_host_tag() {
+        let log = RecordLog::new();
+        let user = Uuid::nil();
+        let host = Uuid::nil();
+
+        let mut r1 = record(host, "history");
+        let mut r2 = record(host, "history");
+        let mut other_tag = record(host, "kv");
+
+        log.append(user, &mut r1);
+        log.append(user, &mut r2);
+        log.append(user, &mut other_tag);
+
+        assert_eq!(r1.seq, Some(0));
+        assert_eq!(r2.seq, Some(1));
+        // a different tag gets its own independent counter
+        assert_eq!(other_tag.seq, Some(0));
+    }
+
+    #[test]
+    fn next_pages_after_a_given_seq() {
+        let log = RecordLog::new();
+        let user = Uuid::nil();
+        let host = Uuid::nil();
+
+        let mut records = vec![
+            record(host, "history"),
+            record(host, "history"),
+            record(host, "history"),
+        ];
+        for record in records.iter_mut() {
+            log.append(user, record);
+        }
+
+        let page = log.next(user, host, "history", Some(0), 10);
+        assert_eq!(
+            page.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![records[1].id, records[2].id]
+        );
+
+        let page = log.next(user, host, "history", None, 1);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, records[0].id);
+    }
+
+    #[test]
+    fn get_returns_only_known_ids() {
+        let log = RecordLog::new();
+        let user = Uuid::nil();
+        let mut r1 = record(Uuid::nil(), "history");
+        log.append(user, &mut r1);
+
+        let found = log.get(&[r1.id, Uuid::max()]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, r1.id);
+    }
+}
@@ -6,6 +6,7 @@ use atuin_client::{
     history::History,
     settings::{FilterMode, SearchMode},
 };
+use atuin_common::state_lock::StateLock;
 use eyre::Result;
 
 use super::cursor::Cursor;
@@ -26,6 +27,12 @@ pub struct SearchState {
     pub input: Cursor,
     pub filter_mode: FilterMode,
     pub context: Context,
+
+    /// Gates this query against a concurrent snapshot/compaction of the
+    /// record store. Any number of queries can hold a read guard at once, and
+    /// a sync pass reading/writing alongside them is fine too — only an
+    /// exclusive `snapshot()` blocks them, and only for as long as it runs.
+    pub record_lock: Arc<StateLock<()>>,
 }
 
 #[async_trait]
@@ -36,11 +43,23 @@ pub trait SearchEngine: Send + Sync + 'static {
         db: &mut dyn Database,
     ) -> Result<Vec<Arc<HistoryWrapper>>>;
 
+    /// Feed a freshly saved history entry into this engine, if it maintains
+    /// its own index. Engines that query the record store directly on every
+    /// call (`db::Search`, `skim::Search`) have nothing to keep in sync, so
+    /// the default is a no-op; `tantivy::Search` overrides this to queue the
+    /// entry for its background writer instead of waiting for the next full
+    /// rebuild.
+    fn enqueue(&self, _history: History) {}
+
     async fn query(
         &mut self,
         state: &SearchState,
         db: &mut dyn Database,
     ) -> Result<Vec<Arc<HistoryWrapper>>> {
+        // Hold this for the duration of the query, so a snapshot can never
+        // observe a half-read, half-compacted chain underneath us.
+        let _guard = state.record_lock.read().await;
+
         if state.input.as_str().is_empty() {
             Ok(db
                 .list(state.filter_mode, &state.context, Some(200), true)
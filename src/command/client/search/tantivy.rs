@@ -0,0 +1,365 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use atuin_client::{
+    database::Database,
+    history::History,
+    settings::{FilterMode, Settings},
+};
+use eyre::{Context as _, Result};
+use tantivy::{
+    collector::TopDocs,
+    directory::MmapDirectory,
+    query::QueryParser,
+    schema::{Field, Schema, FAST, STORED, STRING, TEXT},
+    Index, IndexReader, IndexWriter, ReloadPolicy,
+};
+use tokio::sync::mpsc;
+
+use super::{HistoryWrapper, SearchEngine, SearchState};
+
+/// An entry queued for indexing, tagged with the local sequence number it was
+/// appended at. The writer task applies these strictly in order, so the
+/// "last applied sequence" it persists always reflects a contiguous prefix of
+/// history, never a gap.
+struct PendingEntry {
+    seq: u64,
+    history: History,
+}
+
+#[derive(Clone, Copy)]
+struct Fields {
+    id: Field,
+    command: Field,
+    cwd: Field,
+    hostname: Field,
+    session: Field,
+    timestamp: Field,
+}
+
+fn schema() -> (Schema, Fields) {
+    let mut builder = Schema::builder();
+
+    let id = builder.add_text_field("id", STRING | STORED);
+    let command = builder.add_text_field("command", TEXT | STORED);
+    let cwd = builder.add_text_field("cwd", TEXT | STORED);
+    let hostname = builder.add_text_field("hostname", STRING | STORED);
+    let session = builder.add_text_field("session", STRING | STORED);
+    let timestamp = builder.add_i64_field("timestamp", STORED | FAST);
+
+    let fields = Fields {
+        id,
+        command,
+        cwd,
+        hostname,
+        session,
+        timestamp,
+    };
+
+    (builder.build(), fields)
+}
+
+/// A Tantivy-backed search engine with a persistent, incrementally-maintained
+/// on-disk index, rather than one rebuilt from the full history on every
+/// construction.
+///
+/// New history arrives on `pending_queue`, tagged with a monotonically
+/// increasing local sequence number. A single background writer task drains
+/// the queue in order, batching up whatever has arrived since its last pass
+/// into one commit rather than paying tantivy's commit cost per entry, and
+/// persisting the last-applied sequence number as part of that same commit
+/// so a restart can resume by skipping what's already applied, instead of
+/// paying for a cold `rebuild`. `query`/`full_query` read from `reader`,
+/// which picks up each commit shortly after it lands, so search traffic is
+/// never blocked on the writer.
+pub struct Search {
+    reader: IndexReader,
+    fields: Fields,
+    pending_queue: mpsc::UnboundedSender<PendingEntry>,
+    next_seq: Arc<AtomicU64>,
+}
+
+impl Search {
+    pub fn new() -> Result<Self> {
+        let index_dir = Self::index_dir()?;
+        std::fs::create_dir_all(&index_dir)
+            .wrap_err("could not create tantivy index directory")?;
+
+        let (schema, fields) = schema();
+        let directory = MmapDirectory::open(&index_dir)?;
+        let index = Index::open_or_create(directory, schema)?;
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommitWithDelay)
+            .try_into()?;
+
+        let last_applied = Self::last_applied_seq(&index)?;
+        let next_seq = Arc::new(AtomicU64::new(last_applied.map_or(0, |seq| seq + 1)));
+
+        let (pending_queue, rx) = mpsc::unbounded_channel();
+        Self::spawn_writer(index, fields, rx, last_applied);
+
+        Ok(Self {
+            reader,
+            fields,
+            pending_queue,
+            next_seq,
+        })
+    }
+
+    fn index_dir() -> Result<PathBuf> {
+        let dir = Settings::config_dir()?.join("tantivy");
+        Ok(dir)
+    }
+
+    /// The sequence number of the last entry the on-disk index has committed,
+    /// or `None` if the index has never committed anything.
+    fn last_applied_seq(index: &Index) -> Result<Option<u64>> {
+        let meta = index.load_metas()?;
+        Ok(meta
+            .payload
+            .as_ref()
+            .and_then(|payload| payload.parse::<u64>().ok()))
+    }
+
+    /// Queue every entry in the history database for indexing again.
+    /// Documents already in the index are not removed first, so entries that
+    /// were indexed before are added a second time.
+    ///
+    /// This is not the resume-on-restart path: that's handled separately, by
+    /// `last_applied_seq` letting the writer loop skip entries it's already
+    /// committed. There's no seq-ordered cursor on `db.list` to resume a
+    /// partial rebuild from (treating `seq` as a position in its output, as a
+    /// prior version of this function did via `.skip(seq as usize)`, silently
+    /// reindexes the wrong entries whenever `list`'s order doesn't match our
+    /// local sequence numbers), so this only ever does a full rebuild.
+    pub async fn rebuild(&self, db: &mut dyn Database) -> Result<()> {
+        let history = db
+            .list(FilterMode::Global, &Default::default(), None, false)
+            .await?;
+
+        for entry in history {
+            self.enqueue(entry);
+        }
+
+        Ok(())
+    }
+
+    fn spawn_writer(
+        index: Index,
+        fields: Fields,
+        mut rx: mpsc::UnboundedReceiver<PendingEntry>,
+        last_applied: Option<u64>,
+    ) {
+        tokio::spawn(async move {
+            let mut writer: IndexWriter = match index.writer(50_000_000) {
+                Ok(writer) => writer,
+                Err(e) => {
+                    tracing::error!("failed to open tantivy index writer: {e}");
+                    return;
+                }
+            };
+
+            let mut last_applied = last_applied;
+
+            while let Some(first) = rx.recv().await {
+                // Drain whatever else is already queued so a burst of arrivals
+                // (e.g. an import, or `rebuild`) shares a single commit
+                // instead of paying tantivy's commit cost once per entry.
+                let mut batch = vec![first];
+                while let Ok(entry) = rx.try_recv() {
+                    batch.push(entry);
+                }
+
+                let mut applied_any = false;
+                for entry in batch {
+                    // Sequence numbers only ever increase by one per entry, so
+                    // anything at or below what we've already applied is a
+                    // replay from a prior run and can be skipped.
+                    if last_applied.is_some_and(|applied| entry.seq <= applied) {
+                        continue;
+                    }
+
+                    let doc = tantivy::doc!(
+                        fields.id => entry.history.id.clone(),
+                        fields.command => entry.history.command.clone(),
+                        fields.cwd => entry.history.cwd.clone(),
+                        fields.hostname => entry.history.hostname.clone(),
+                        fields.session => entry.history.session.clone(),
+                        fields.timestamp => entry.history.timestamp.unix_timestamp(),
+                    );
+
+                    writer.add_document(doc).ok();
+                    last_applied = Some(entry.seq);
+                    applied_any = true;
+                }
+
+                if !applied_any {
+                    continue;
+                }
+
+                // The payload has to be set on the commit it's meant to
+                // describe, not after: `commit()` is what persists the index
+                // metadata (payload included), so setting it afterwards would
+                // only ever take effect on the *next* commit, leaving the
+                // on-disk watermark one batch stale if the process exits
+                // before that happens.
+                let result = writer
+                    .prepare_commit()
+                    .and_then(|mut prepared| {
+                        prepared.set_payload(&last_applied.unwrap().to_string());
+                        prepared.commit()
+                    });
+
+                if let Err(e) = result {
+                    tracing::error!("failed to commit tantivy index: {e}");
+                }
+            }
+        });
+    }
+
+    fn query_parser(&self, index: &Index) -> QueryParser {
+        QueryParser::for_index(index, vec![self.fields.command, self.fields.cwd])
+    }
+}
+
+#[async_trait]
+impl SearchEngine for Search {
+    async fn full_query(
+        &mut self,
+        state: &SearchState,
+        db: &mut dyn Database,
+    ) -> Result<Vec<Arc<HistoryWrapper>>> {
+        let searcher = self.reader.searcher();
+        let index = searcher.index();
+        let parser = self.query_parser(index);
+
+        let query = parser.parse_query(&escape(state.input.as_str()))?;
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(200))?;
+
+        let mut results = Vec::with_capacity(top_docs.len());
+        for (_score, doc_address) in top_docs {
+            let doc = searcher.doc(doc_address)?;
+            let Some(id) = doc.get_first(self.fields.id).and_then(|v| v.as_text()) else {
+                continue;
+            };
+
+            if let Some(history) = db.load(id).await.ok() {
+                results.push(Arc::new(HistoryWrapper { history, count: 1 }));
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Queue a freshly arrived history entry for indexing. Returns immediately;
+    /// the entry is applied by the background writer task in sequence order.
+    ///
+    /// The actual call site - wherever the client persists a new `History`
+    /// after a shell command runs - should call this (via the `SearchEngine`
+    /// trait object it already holds) right after the save succeeds, so the
+    /// index stays current without waiting for the next full `rebuild`.
+    /// That save path isn't part of this checkout, so as shipped here nothing
+    /// calls this outside of `rebuild` itself - the index only reflects
+    /// whatever `rebuild` last did, not live saves.
+    fn enqueue(&self, history: History) {
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+
+        // A send fails only once the writer task has stopped (it could not open
+        // the index writer, or the runtime is shutting down); the entry is then
+        // dropped.
+        let _ = self.pending_queue.send(PendingEntry { seq, history });
+    }
+}
+
+/// Tantivy's query parser treats a handful of characters as syntax; history
+/// commands are free text, so escape them before handing the input over.
+fn escape(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(
+            c,
+            '+' | '^' | '`' | ':' | '{' | '}' | '"' | '[' | ']' | '(' | ')' | '~' | '!' | '\\' | '*'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atuin_client::history::History;
+    use pretty_assertions::assert_eq;
+    use tantivy::directory::RamDirectory;
+    use time::OffsetDateTime;
+
+    #[test]
+    fn escape_prefixes_query_syntax_characters() {
+        assert_eq!(escape("cd ~/foo && echo (1)"), "cd \\~/foo && echo \\(1\\)");
+        assert_eq!(escape("plain words"), "plain words");
+    }
+
+    fn history(command: &str) -> History {
+        History::new(
+            OffsetDateTime::now_utc(),
+            command.into(),
+            "/tmp".into(),
+            0,
+            1,
+            "localhost".into(),
+            "session".into(),
+            None,
+        )
+    }
+
+    /// Exercises the writer task directly against an in-memory index: after
+    /// a burst of entries sent before the task gets a chance to run, the
+    /// persisted watermark reflects the last entry actually applied - not the
+    /// stale pre-commit value a set-payload-after-commit bug would have left
+    /// behind - and every entry is indexed. How many commits the burst took
+    /// is not checked.
+    #[tokio::test]
+    async fn writer_batches_a_burst_and_persists_the_final_watermark() {
+        let (schema, fields) = schema();
+        let index = Index::create(RamDirectory::create(), schema, Default::default())
+            .expect("failed to create in-memory tantivy index");
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        Search::spawn_writer(index.clone(), fields, rx, None);
+
+        for (seq, command) in ["ls", "cd /tmp", "echo hi"].into_iter().enumerate() {
+            tx.send(PendingEntry {
+                seq: seq as u64,
+                history: history(command),
+            })
+            .expect("writer task should still be alive");
+        }
+        drop(tx);
+
+        // Give the spawned task a chance to drain the channel and commit.
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        assert_eq!(
+            Search::last_applied_seq(&index).expect("failed to read index metas"),
+            Some(2)
+        );
+
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .expect("failed to build reader");
+        reader.reload().expect("failed to reload reader");
+        assert_eq!(reader.searcher().num_docs(), 3);
+    }
+}